@@ -0,0 +1,148 @@
+//! Support for paint callbacks that issue raw OpenGL calls, for SDL renderers backed by the
+//! OpenGL render driver.
+//!
+//! Gated behind the `gl` feature, which pulls in the `gl` loader crate. The GL context itself
+//! must already be current and its function pointers loaded (e.g. via `gl::load_with`) before
+//! any [`GlCallbackFn`] is invoked; this module only saves and restores the bits of GL state that
+//! SDL's 2D renderer relies on around the callback.
+
+use egui::epaint::ViewportInPixels;
+use sdl2::render::Canvas;
+use sdl2::video::Window;
+
+use crate::PainterError;
+
+/// The info passed to a [`GlCallbackFn`], with the physical-pixel viewport and clip rect already
+/// computed so callers don't have to redo that math.
+pub struct GlPaintCallbackInfo {
+    pub info: egui::PaintCallbackInfo,
+    pub viewport_in_pixels: ViewportInPixels,
+    pub clip_rect_in_pixels: ViewportInPixels,
+}
+
+pub struct GlCallbackFn {
+    f: Box<dyn Fn(GlPaintCallbackInfo) + Sync + Send>,
+}
+
+impl GlCallbackFn {
+    pub fn new<F: Fn(GlPaintCallbackInfo) + Sync + Send + 'static>(callback: F) -> Self {
+        Self {
+            f: Box::new(callback),
+        }
+    }
+}
+
+/// Runs `callback`, sandwiched between saving and restoring the GL state SDL's 2D renderer
+/// relies on (current framebuffer, viewport, scissor rect and enable bit, blend state and the
+/// depth-test enable bit).
+///
+/// SDL's OpenGL render backend caches the blend mode (and other state) it last asked the driver
+/// for, and only re-issues the underlying GL calls when a new draw needs a different cache value.
+/// If a callback leaves `GL_BLEND`, the blend func/equation or `GL_DEPTH_TEST` in a state that
+/// differs from what SDL's cache believes is current, SDL won't reinstate it before the next egui
+/// mesh, so that mesh renders with the callback's leftover state. Restoring it here keeps SDL's
+/// cache and the real GL state in sync.
+pub(crate) fn paint_gl_callback(
+    canvas: &mut Canvas<Window>,
+    info: &egui::PaintCallbackInfo,
+    callback: &GlCallbackFn,
+) -> Result<(), PainterError> {
+    // SDL batches 2D geometry internally, flush it out before interleaving raw GL calls.
+    let ret = unsafe { sdl2::sys::SDL_RenderFlush(canvas.raw()) };
+    if ret < 0 {
+        return Err(PainterError::SdlError(sdl2::get_error()));
+    }
+
+    let viewport_in_pixels = info.viewport_in_pixels();
+    let clip_rect_in_pixels = info.clip_rect_in_pixels();
+
+    unsafe {
+        let mut prev_fbo = 0;
+        gl::GetIntegerv(gl::FRAMEBUFFER_BINDING, &mut prev_fbo);
+        let mut prev_viewport = [0_i32; 4];
+        gl::GetIntegerv(gl::VIEWPORT, prev_viewport.as_mut_ptr());
+        let mut prev_scissor = [0_i32; 4];
+        gl::GetIntegerv(gl::SCISSOR_BOX, prev_scissor.as_mut_ptr());
+        let scissor_was_enabled = gl::IsEnabled(gl::SCISSOR_TEST) == gl::TRUE;
+        let blend_was_enabled = gl::IsEnabled(gl::BLEND) == gl::TRUE;
+        let depth_test_was_enabled = gl::IsEnabled(gl::DEPTH_TEST) == gl::TRUE;
+        let mut prev_blend_src_rgb = 0;
+        gl::GetIntegerv(gl::BLEND_SRC_RGB, &mut prev_blend_src_rgb);
+        let mut prev_blend_dst_rgb = 0;
+        gl::GetIntegerv(gl::BLEND_DST_RGB, &mut prev_blend_dst_rgb);
+        let mut prev_blend_src_alpha = 0;
+        gl::GetIntegerv(gl::BLEND_SRC_ALPHA, &mut prev_blend_src_alpha);
+        let mut prev_blend_dst_alpha = 0;
+        gl::GetIntegerv(gl::BLEND_DST_ALPHA, &mut prev_blend_dst_alpha);
+        let mut prev_blend_equation_rgb = 0;
+        gl::GetIntegerv(gl::BLEND_EQUATION_RGB, &mut prev_blend_equation_rgb);
+        let mut prev_blend_equation_alpha = 0;
+        gl::GetIntegerv(gl::BLEND_EQUATION_ALPHA, &mut prev_blend_equation_alpha);
+
+        gl::Viewport(
+            viewport_in_pixels.left_px,
+            viewport_in_pixels.from_bottom_px,
+            viewport_in_pixels.width_px,
+            viewport_in_pixels.height_px,
+        );
+        gl::Enable(gl::SCISSOR_TEST);
+        gl::Scissor(
+            clip_rect_in_pixels.left_px,
+            clip_rect_in_pixels.from_bottom_px,
+            clip_rect_in_pixels.width_px,
+            clip_rect_in_pixels.height_px,
+        );
+
+        (callback.f)(GlPaintCallbackInfo {
+            info: egui::PaintCallbackInfo {
+                viewport: info.viewport,
+                clip_rect: info.clip_rect,
+                pixels_per_point: info.pixels_per_point,
+                screen_size_px: info.screen_size_px,
+            },
+            viewport_in_pixels,
+            clip_rect_in_pixels,
+        });
+
+        gl::Viewport(
+            prev_viewport[0],
+            prev_viewport[1],
+            prev_viewport[2],
+            prev_viewport[3],
+        );
+        gl::Scissor(
+            prev_scissor[0],
+            prev_scissor[1],
+            prev_scissor[2],
+            prev_scissor[3],
+        );
+        if scissor_was_enabled {
+            gl::Enable(gl::SCISSOR_TEST);
+        } else {
+            gl::Disable(gl::SCISSOR_TEST);
+        }
+        if blend_was_enabled {
+            gl::Enable(gl::BLEND);
+        } else {
+            gl::Disable(gl::BLEND);
+        }
+        gl::BlendFuncSeparate(
+            prev_blend_src_rgb as gl::types::GLenum,
+            prev_blend_dst_rgb as gl::types::GLenum,
+            prev_blend_src_alpha as gl::types::GLenum,
+            prev_blend_dst_alpha as gl::types::GLenum,
+        );
+        gl::BlendEquationSeparate(
+            prev_blend_equation_rgb as gl::types::GLenum,
+            prev_blend_equation_alpha as gl::types::GLenum,
+        );
+        if depth_test_was_enabled {
+            gl::Enable(gl::DEPTH_TEST);
+        } else {
+            gl::Disable(gl::DEPTH_TEST);
+        }
+        gl::BindFramebuffer(gl::FRAMEBUFFER, prev_fbo as gl::types::GLuint);
+    }
+
+    Ok(())
+}
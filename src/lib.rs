@@ -1,5 +1,4 @@
 use std::collections::HashMap;
-use std::collections::hash_map::Entry;
 use std::fmt;
 use std::mem::offset_of;
 
@@ -15,6 +14,31 @@ use sdl2::video::{Window, WindowContext};
 
 pub use sdl2;
 
+#[cfg(feature = "gl")]
+mod gl_callback;
+#[cfg(feature = "gl")]
+pub use gl_callback::{GlCallbackFn, GlPaintCallbackInfo};
+
+#[cfg(feature = "gl")]
+fn try_paint_gl_callback(
+    canvas: &mut Canvas<Window>,
+    info: &PaintCallbackInfo,
+    callback: &(dyn std::any::Any + Send + Sync),
+) -> Option<Result<(), PainterError>> {
+    callback
+        .downcast_ref::<gl_callback::GlCallbackFn>()
+        .map(|callback| gl_callback::paint_gl_callback(canvas, info, callback))
+}
+
+#[cfg(not(feature = "gl"))]
+fn try_paint_gl_callback(
+    _canvas: &mut Canvas<Window>,
+    _info: &PaintCallbackInfo,
+    _callback: &(dyn std::any::Any + Send + Sync),
+) -> Option<Result<(), PainterError>> {
+    None
+}
+
 #[derive(Debug, Clone)]
 pub enum PainterError {
     SdlRenderGeometryUnsupported,
@@ -24,6 +48,9 @@ pub enum PainterError {
     FreeInvalidTexture(TextureId),
     PaintInvalidTexture(TextureId),
     BlendModeNotSupported,
+    WrapModeUnsupported(egui::TextureWrapMode),
+    UnknownViewport(egui::ViewportId),
+    RenderTargetsNotSupported,
 }
 
 impl From<UpdateTextureError> for PainterError {
@@ -65,6 +92,21 @@ impl fmt::Display for PainterError {
             Self::BlendModeNotSupported => {
                 write!(f, "blend mode needed by egui not supported")
             }
+            Self::WrapModeUnsupported(wrap_mode) => {
+                write!(
+                    f,
+                    "texture wrap mode {wrap_mode:?} not supported: SDL's 2D renderer does not support per-texture wrap modes",
+                )
+            }
+            Self::UnknownViewport(viewport_id) => {
+                write!(f, "no painter registered for viewport {viewport_id:?}")
+            }
+            Self::RenderTargetsNotSupported => {
+                write!(
+                    f,
+                    "the current SDL renderer does not support render targets"
+                )
+            }
         }
     }
 }
@@ -78,7 +120,10 @@ impl std::error::Error for PainterError {
             | Self::SdlError(_)
             | Self::FreeInvalidTexture(_)
             | Self::PaintInvalidTexture(_)
-            | Self::BlendModeNotSupported => None,
+            | Self::BlendModeNotSupported
+            | Self::WrapModeUnsupported(_)
+            | Self::UnknownViewport(_)
+            | Self::RenderTargetsNotSupported => None,
         }
     }
 }
@@ -98,10 +143,91 @@ impl CallbackFn {
     }
 }
 
+/// How many `ImageDelta`s a texture has to receive before it is worth paying for the switch to
+/// `TextureAccess::Streaming`.
+const STREAMING_THRESHOLD: u32 = 2;
+
+struct ManagedTexture<'texture> {
+    texture: Texture<'texture>,
+    /// Tightly packed (stride = `width * 4`) RGBA8 copy of the texture's full current contents.
+    /// Kept around so that switching a texture over to `TextureAccess::Streaming` mid-lifetime
+    /// doesn't lose whatever an earlier `ImageDelta` already wrote to it.
+    pixels: Vec<u8>,
+    width: u32,
+    height: u32,
+    streaming: bool,
+    update_count: u32,
+    // last `TextureOptions` applied to the current SDL texture, so we only touch the SDL scale
+    // mode when it actually changed, and so a freshly (re)created texture always gets one applied
+    options: Option<egui::TextureOptions>,
+}
+
+// egui meshes are premultiplied-alpha, and SDL has no premultiplied blend mode built in, so
+// compose the custom blend equation that treats both source and destination as premultiplied.
+fn set_premultiplied_blend_mode(texture: &Texture) -> Result<(), PainterError> {
+    use sdl2::sys::{SDL_BlendFactor, SDL_BlendOperation, SDL_ComposeCustomBlendMode};
+
+    // TODO use safe binding coming in sdl2 v0.39 (https://github.com/Rust-SDL2/rust-sdl2/pull/1507)
+    let blend_mode = unsafe {
+        SDL_ComposeCustomBlendMode(
+            SDL_BlendFactor::SDL_BLENDFACTOR_ONE,
+            SDL_BlendFactor::SDL_BLENDFACTOR_ONE_MINUS_SRC_ALPHA,
+            SDL_BlendOperation::SDL_BLENDOPERATION_ADD,
+            SDL_BlendFactor::SDL_BLENDFACTOR_ONE_MINUS_DST_ALPHA,
+            SDL_BlendFactor::SDL_BLENDFACTOR_ONE,
+            SDL_BlendOperation::SDL_BLENDOPERATION_ADD,
+        )
+    };
+
+    let ret = unsafe { sdl2::sys::SDL_SetTextureBlendMode(texture.raw(), blend_mode) };
+    if ret < 0 {
+        return Err(PainterError::BlendModeNotSupported);
+    }
+
+    Ok(())
+}
+
+fn create_texture<'texture>(
+    texture_creator: &'texture TextureCreator<WindowContext>,
+    access: TextureAccess,
+    width: u32,
+    height: u32,
+) -> Result<Texture<'texture>, PainterError> {
+    let texture = texture_creator.create_texture(PixelFormatEnum::RGBA32, access, width, height)?;
+    set_premultiplied_blend_mode(&texture)?;
+    Ok(texture)
+}
+
+// SDL's 2D renderer only exposes a single scale mode per texture, so the magnification filter is
+// treated as authoritative and the minification filter is ignored.
+fn apply_scale_mode(
+    managed: &mut ManagedTexture<'_>,
+    options: egui::TextureOptions,
+) -> Result<(), PainterError> {
+    if managed.options == Some(options) {
+        return Ok(());
+    }
+
+    let scale_mode = match options.magnification {
+        egui::TextureFilter::Nearest => sdl2::sys::SDL_ScaleMode::SDL_ScaleModeNearest,
+        egui::TextureFilter::Linear => sdl2::sys::SDL_ScaleMode::SDL_ScaleModeLinear,
+    };
+
+    // TODO use safe binding coming in sdl2 v0.39 (https://github.com/Rust-SDL2/rust-sdl2/pull/1507)
+    let ret = unsafe { sdl2::sys::SDL_SetTextureScaleMode(managed.texture.raw(), scale_mode) };
+    if ret < 0 {
+        return Err(PainterError::SdlError(sdl2::get_error()));
+    }
+
+    managed.options = Some(options);
+
+    Ok(())
+}
+
 pub struct Painter<'texture> {
     texture_creator: &'texture TextureCreator<WindowContext>,
     // TODO rustc-hash?
-    textures: HashMap<TextureId, Texture<'texture>>,
+    textures: HashMap<TextureId, ManagedTexture<'texture>>,
 }
 
 impl<'texture> Painter<'texture> {
@@ -117,45 +243,9 @@ impl<'texture> Painter<'texture> {
         id: TextureId,
         delta: &ImageDelta,
     ) -> Result<(), PainterError> {
-        use sdl2::sys::{SDL_BlendFactor, SDL_BlendOperation, SDL_ComposeCustomBlendMode};
-
-        // TODO use safe binding coming in sdl2 v0.39 (https://github.com/Rust-SDL2/rust-sdl2/pull/1507)
-        let blend_mode = unsafe {
-            SDL_ComposeCustomBlendMode(
-                SDL_BlendFactor::SDL_BLENDFACTOR_ONE,
-                SDL_BlendFactor::SDL_BLENDFACTOR_ONE_MINUS_SRC_ALPHA,
-                SDL_BlendOperation::SDL_BLENDOPERATION_ADD,
-                SDL_BlendFactor::SDL_BLENDFACTOR_ONE_MINUS_DST_ALPHA,
-                SDL_BlendFactor::SDL_BLENDFACTOR_ONE,
-                SDL_BlendOperation::SDL_BLENDOPERATION_ADD,
-            )
-        };
-
-        let [x, y] = delta
-            .pos
-            .map(|pos| pos.map(|coord| coord.try_into().unwrap()))
-            .unwrap_or([0, 0]);
-        let width = delta.image.width().try_into().unwrap();
-        let height = delta.image.height().try_into().unwrap();
-
-        let texture = match self.textures.entry(id) {
-            Entry::Occupied(e) => e.into_mut(),
-            Entry::Vacant(e) => e.insert({
-                let texture = self.texture_creator.create_texture(
-                    PixelFormatEnum::RGBA32,
-                    TextureAccess::Static,
-                    width,
-                    height,
-                )?;
-
-                let ret = unsafe { sdl2::sys::SDL_SetTextureBlendMode(texture.raw(), blend_mode) };
-                if ret < 0 {
-                    return Err(PainterError::BlendModeNotSupported);
-                }
-
-                texture
-            }),
-        };
+        if delta.options.wrap_mode != egui::TextureWrapMode::ClampToEdge {
+            return Err(PainterError::WrapModeUnsupported(delta.options.wrap_mode));
+        }
 
         let egui::ImageData::Color(image) = &delta.image;
 
@@ -165,30 +255,179 @@ impl<'texture> Painter<'texture> {
             "Mismatch between texture size and texel count",
         );
 
-        let pixels: *const [Color32] = image.pixels.as_slice();
+        let delta_pixels: *const [Color32] = image.pixels.as_slice();
         // SAFETY: `Color32` just wraps `[u8; 4]` and is repr(C)
-        let data = unsafe { &*(pixels as *const [[u8; 4]]) }.as_flattened();
-
-        // TODO
-        // let TextureOptions { magnification, minification, wrap_mode } = delta.options;
-        // filter mode can only be set for both magnification and minification
-        // sdl2::hint::set("SDL_RENDER_SCALE_QUALITY", "nearest");
-        // sdl2::hint::set("SDL_RENDER_SCALE_QUALITY", "linear");
-        // sdl2 does not support setting wrap mode, sdl3 also does not (there is a closed pr that might be reopened)
-
-        texture.update(
-            Rect::from((x, y, width, height)),
-            data,
-            delta.image.width() * size_of::<Color32>(),
-        )?;
+        let delta_data = unsafe { &*(delta_pixels as *const [[u8; 4]]) }.as_flattened();
 
-        Ok(())
+        let [x, y]: [u32; 2] = delta
+            .pos
+            .map(|pos| pos.map(|coord| coord.try_into().unwrap()))
+            .unwrap_or([0, 0]);
+        let delta_width: u32 = delta.image.width().try_into().unwrap();
+        let delta_height: u32 = delta.image.height().try_into().unwrap();
+
+        // A fresh texture, or a full replacement of a different size (e.g. the atlas outgrew its
+        // previous size): (re)create it from scratch as a `Static` texture, and start counting
+        // deltas again. A same-size full replacement is written in place like a partial update.
+        let needs_recreate = match self.textures.get(&id) {
+            None => true,
+            Some(managed) => {
+                delta.pos.is_none()
+                    && (delta_width != managed.width || delta_height != managed.height)
+            }
+        };
+
+        if needs_recreate {
+            let texture = create_texture(
+                self.texture_creator,
+                TextureAccess::Static,
+                delta_width,
+                delta_height,
+            )?;
+
+            self.textures.insert(
+                id,
+                ManagedTexture {
+                    texture,
+                    pixels: delta_data.to_vec(),
+                    width: delta_width,
+                    height: delta_height,
+                    streaming: false,
+                    update_count: 1,
+                    options: None,
+                },
+            );
+
+            return apply_scale_mode(self.textures.get_mut(&id).unwrap(), delta.options);
+        }
+
+        let managed = self.textures.get_mut(&id).unwrap();
+        managed.update_count += 1;
+
+        // Patch the backing buffer first: on the delta that triggers the switch to `Streaming`
+        // this already contains this delta's contents, so the migration below can upload it in
+        // one go.
+        for row in 0..delta_height as usize {
+            let src_row = row * delta_width as usize * 4..(row + 1) * delta_width as usize * 4;
+            let dst_start = ((y as usize + row) * managed.width as usize + x as usize) * 4;
+            managed.pixels[dst_start..dst_start + delta_width as usize * 4]
+                .copy_from_slice(&delta_data[src_row]);
+        }
+
+        if !managed.streaming && managed.update_count >= STREAMING_THRESHOLD {
+            let mut texture = create_texture(
+                self.texture_creator,
+                TextureAccess::Streaming,
+                managed.width,
+                managed.height,
+            )?;
+
+            let (width, height, pixels) = (
+                managed.width as usize,
+                managed.height as usize,
+                &managed.pixels,
+            );
+            texture
+                .with_lock(None, |dst, pitch| {
+                    for row in 0..height {
+                        let src_row = row * width * 4..(row + 1) * width * 4;
+                        let dst_row = row * pitch..row * pitch + width * 4;
+                        dst[dst_row].copy_from_slice(&pixels[src_row]);
+                    }
+                })
+                .map_err(PainterError::SdlError)?;
+
+            managed.texture = texture;
+            managed.streaming = true;
+            // the SDL texture object was just replaced, so its scale mode needs to be re-applied
+            managed.options = None;
+        } else if managed.streaming {
+            managed
+                .texture
+                .with_lock(
+                    Some(Rect::from((x as i32, y as i32, delta_width, delta_height))),
+                    |dst, pitch| {
+                        for row in 0..delta_height as usize {
+                            let src_row = row * delta_width as usize * 4
+                                ..(row + 1) * delta_width as usize * 4;
+                            let dst_row = row * pitch..row * pitch + delta_width as usize * 4;
+                            dst[dst_row].copy_from_slice(&delta_data[src_row]);
+                        }
+                    },
+                )
+                .map_err(PainterError::SdlError)?;
+        } else {
+            managed.texture.update(
+                Rect::from((x as i32, y as i32, delta_width, delta_height)),
+                delta_data,
+                delta_width as usize * size_of::<Color32>(),
+            )?;
+        }
+
+        apply_scale_mode(managed, delta.options)
     }
 
     fn free_texture(&mut self, id: TextureId) -> bool {
         self.textures.remove(&id).is_some()
     }
 
+    /// Creates `id` from another painter's already-uploaded [`ManagedTexture`], by copying its
+    /// backing pixels. Used by [`MultiWindowPainter::add_viewport`] to backfill a newly-registered
+    /// viewport with textures `set` before it existed, since egui only sends a texture's `set`
+    /// once.
+    fn clone_texture_from(
+        &mut self,
+        id: TextureId,
+        source: &ManagedTexture<'_>,
+    ) -> Result<(), PainterError> {
+        let access = if source.streaming {
+            TextureAccess::Streaming
+        } else {
+            TextureAccess::Static
+        };
+        let mut texture =
+            create_texture(self.texture_creator, access, source.width, source.height)?;
+
+        if source.streaming {
+            texture
+                .with_lock(None, |dst, pitch| {
+                    for row in 0..source.height as usize {
+                        let src_row =
+                            row * source.width as usize * 4..(row + 1) * source.width as usize * 4;
+                        let dst_row = row * pitch..row * pitch + source.width as usize * 4;
+                        dst[dst_row].copy_from_slice(&source.pixels[src_row]);
+                    }
+                })
+                .map_err(PainterError::SdlError)?;
+        } else {
+            texture.update(
+                None,
+                &source.pixels,
+                source.width as usize * size_of::<Color32>(),
+            )?;
+        }
+
+        let mut managed = ManagedTexture {
+            texture,
+            pixels: source.pixels.clone(),
+            width: source.width,
+            height: source.height,
+            streaming: source.streaming,
+            update_count: source.update_count,
+            options: None,
+        };
+        // `source.options` is always `Some` by the time a texture is visible outside
+        // `update_or_create_texture`, since that function always applies a scale mode right after
+        // creating one.
+        apply_scale_mode(
+            &mut managed,
+            source.options.expect("texture missing scale mode"),
+        )?;
+
+        self.textures.insert(id, managed);
+        Ok(())
+    }
+
     pub fn paint_and_update_textures(
         &mut self,
         canvas: &mut Canvas<Window>,
@@ -201,13 +440,107 @@ impl<'texture> Painter<'texture> {
             self.update_or_create_texture(*id, delta)?;
         }
 
+        self.paint_primitives(canvas, screen_size_px, pixels_per_point, clipped_primitives)?;
+
+        for &id in &textures_delta.free {
+            if !self.free_texture(id) {
+                return Err(PainterError::FreeInvalidTexture(id));
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Like [`Self::paint_and_update_textures`], but renders into `target` (an SDL
+    /// [`TextureAccess::Target`] texture) instead of the window. Useful for compositing the egui
+    /// layer over other content, capturing a screenshot of the UI, or rendering it at a different
+    /// resolution than the window for supersampling: if `target` is larger than `screen_size_px`,
+    /// the UI is scaled up to fill it, clip rects included.
+    ///
+    /// `canvas`'s draw color, blend mode, clip rect and scale are left unchanged on return.
+    pub fn paint_to_texture(
+        &mut self,
+        canvas: &mut Canvas<Window>,
+        target: &mut Texture<'texture>,
+        screen_size_px: [u32; 2],
+        pixels_per_point: f32,
+        clipped_primitives: &[egui::ClippedPrimitive],
+        textures_delta: &egui::TexturesDelta,
+    ) -> Result<(), PainterError> {
+        if !canvas.render_target_supported() {
+            return Err(PainterError::RenderTargetsNotSupported);
+        }
+
+        for (id, delta) in &textures_delta.set {
+            self.update_or_create_texture(*id, delta)?;
+        }
+
+        set_premultiplied_blend_mode(target)?;
+
+        // Mesh vertices and clip rects from `clipped_primitives` are in points, while
+        // `screen_size_px` and `target`'s dimensions are physical pixels, so converting between
+        // the two needs `pixels_per_point` folded in (points -> target pixels, via physical
+        // pixels): `pixels_per_point * target_px / screen_size_px`.
+        let target_query = target.query();
+        let scale_x = pixels_per_point * target_query.width as f32 / screen_size_px[0] as f32;
+        let scale_y = pixels_per_point * target_query.height as f32 / screen_size_px[1] as f32;
+
+        let prev_draw_color = canvas.draw_color();
+        let prev_blend_mode = canvas.blend_mode();
+        let prev_clip_rect = canvas.clip_rect();
+        let prev_scale = canvas.scale();
+
+        let mut result = Ok(());
+        canvas
+            .with_texture_canvas(target, |target_canvas| {
+                result = target_canvas
+                    .set_scale(scale_x, scale_y)
+                    .map_err(PainterError::SdlError)
+                    .and_then(|()| {
+                        self.paint_primitives(
+                            target_canvas,
+                            screen_size_px,
+                            pixels_per_point,
+                            clipped_primitives,
+                        )
+                    });
+            })
+            .map_err(|err| PainterError::SdlError(err.to_string()))?;
+
+        canvas.set_draw_color(prev_draw_color);
+        canvas.set_blend_mode(prev_blend_mode);
+        canvas.set_clip_rect(prev_clip_rect);
+        let (prev_scale_x, prev_scale_y) = prev_scale;
+        canvas
+            .set_scale(prev_scale_x, prev_scale_y)
+            .map_err(PainterError::SdlError)?;
+
+        result?;
+
+        for &id in &textures_delta.free {
+            if !self.free_texture(id) {
+                return Err(PainterError::FreeInvalidTexture(id));
+            }
+        }
+
+        Ok(())
+    }
+
+    fn paint_primitives(
+        &self,
+        canvas: &mut Canvas<Window>,
+        screen_size_px: [u32; 2],
+        pixels_per_point: f32,
+        clipped_primitives: &[egui::ClippedPrimitive],
+    ) -> Result<(), PainterError> {
         for p in clipped_primitives {
             match &p.primitive {
                 Primitive::Mesh(mesh) => {
-                    let texture = self
+                    let texture = &self
                         .textures
                         .get(&mesh.texture_id)
-                        .ok_or(PainterError::PaintInvalidTexture(mesh.texture_id))?;
+                        .ok_or(PainterError::PaintInvalidTexture(mesh.texture_id))?
+                        .texture;
 
                     let clip_size = p.clip_rect.size();
                     canvas.set_clip_rect(Rect::from((
@@ -243,16 +576,106 @@ impl<'texture> Painter<'texture> {
 
                     if let Some(callback) = paint_callback.callback.downcast_ref::<CallbackFn>() {
                         (callback.f)(info, self, canvas);
+                    } else if let Some(result) =
+                        try_paint_gl_callback(canvas, &info, paint_callback.callback.as_ref())
+                    {
+                        result?;
                     } else {
-                        // eprintln!("invalid callback, expected egui_sdl2_renderer::CallbackFn");
+                        // eprintln!("invalid callback, expected egui_sdl2_renderer::CallbackFn or GlCallbackFn");
                     }
                 }
             }
         }
 
+        Ok(())
+    }
+}
+
+/// Renders egui's multi-viewport output (one OS window per [`egui::ViewportId`]) by keeping a
+/// [`Painter`] per window.
+///
+/// The shared font/image atlas (`TextureId::Managed(0)`) is produced once by egui but is needed
+/// by every viewport, since each SDL [`Texture`] is tied to the [`TextureCreator`] of the window
+/// it was created for and cannot be shared across windows. [`Self::paint_viewport`] therefore
+/// replicates every `set`/`free` delta to all registered painters before painting the requested
+/// viewport, and [`Self::add_viewport`] backfills a newly-registered painter with every texture
+/// already uploaded to the others, since egui won't send a texture's `set` delta a second time for
+/// a viewport that's only just been opened.
+#[derive(Default)]
+pub struct MultiWindowPainter<'texture> {
+    painters: HashMap<egui::ViewportId, Painter<'texture>>,
+}
+
+impl<'texture> MultiWindowPainter<'texture> {
+    pub fn new() -> Self {
+        Self {
+            painters: HashMap::new(),
+        }
+    }
+
+    /// Registers a window as a target for a viewport, to be called when egui opens it.
+    ///
+    /// Viewports are commonly opened well after startup (tooltips, detached panels), but egui only
+    /// sends a texture's `set` delta once, so a fresh, empty [`Painter`] would fail to paint
+    /// anything that was already uploaded to the other viewports (most importantly the shared font
+    /// atlas). To avoid that, every texture already known to an existing viewport is copied into
+    /// the new one here.
+    ///
+    /// Does nothing if `viewport_id` is already registered.
+    pub fn add_viewport(
+        &mut self,
+        viewport_id: egui::ViewportId,
+        texture_creator: &'texture TextureCreator<WindowContext>,
+    ) -> Result<(), PainterError> {
+        if self.painters.contains_key(&viewport_id) {
+            return Ok(());
+        }
+
+        let mut painter = Painter::new(texture_creator);
+
+        if let Some(existing) = self.painters.values().next() {
+            for (&id, managed) in &existing.textures {
+                painter.clone_texture_from(id, managed)?;
+            }
+        }
+
+        self.painters.insert(viewport_id, painter);
+        Ok(())
+    }
+
+    /// Unregisters a window, to be called when egui closes its viewport. Returns whether the
+    /// viewport was registered.
+    pub fn remove_viewport(&mut self, viewport_id: egui::ViewportId) -> bool {
+        self.painters.remove(&viewport_id).is_some()
+    }
+
+    pub fn paint_viewport(
+        &mut self,
+        viewport_id: egui::ViewportId,
+        canvas: &mut Canvas<Window>,
+        screen_size_px: [u32; 2],
+        pixels_per_point: f32,
+        clipped_primitives: &[egui::ClippedPrimitive],
+        textures_delta: &egui::TexturesDelta,
+    ) -> Result<(), PainterError> {
+        for (id, delta) in &textures_delta.set {
+            for painter in self.painters.values_mut() {
+                painter.update_or_create_texture(*id, delta)?;
+            }
+        }
+
+        let painter = self
+            .painters
+            .get(&viewport_id)
+            .ok_or(PainterError::UnknownViewport(viewport_id))?;
+
+        painter.paint_primitives(canvas, screen_size_px, pixels_per_point, clipped_primitives)?;
+
         for &id in &textures_delta.free {
-            if !self.free_texture(id) {
-                return Err(PainterError::FreeInvalidTexture(id));
+            for painter in self.painters.values_mut() {
+                if !painter.free_texture(id) {
+                    return Err(PainterError::FreeInvalidTexture(id));
+                }
             }
         }
 